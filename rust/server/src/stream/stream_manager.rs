@@ -15,6 +15,12 @@ use risingwave_proto::stream_service;
 use std::convert::TryFrom;
 use tokio::task::JoinHandle;
 
+mod exchange_service;
+mod remote_exchange;
+
+pub use exchange_service::StreamExchangeService;
+use remote_exchange::{new_remote_input, RemoteOutput};
+
 /// Default capacity of channel if two fragments are on the same node
 pub const LOCAL_OUTPUT_CHANNEL_SIZE: usize = 16;
 
@@ -43,6 +49,11 @@ pub struct StreamManagerCore {
     /// Mock source, `fragment_id = 0`
     /// TODO: remove this
     mock_source: ConsumableChannelPair,
+
+    /// This node's own address, used by `is_local_address` to tell an upstream/downstream actor
+    /// on this same node apart from one that needs to be reached over gRPC. Set once via
+    /// `bind_addr`, analogous to how `actors` is set once via `update_actor_info`.
+    own_addr: Option<stream_service::HostAddress>,
 }
 
 /// `StreamManager` manages all stream operators in this project.
@@ -62,6 +73,15 @@ impl StreamManager {
         core.update_fragment(fragments)
     }
 
+    /// Tells the manager this node's own address, so it can later tell local actors apart from
+    /// ones that need to be reached over gRPC. Call it once before `update_fragment`, which
+    /// already uses it to decide which downstreams get a local channel; until it is called, only
+    /// `127.0.0.1` counts as local.
+    pub fn bind_addr(&self, addr: stream_service::HostAddress) {
+        let mut core = self.core.lock().unwrap();
+        core.own_addr = Some(addr);
+    }
+
     pub async fn wait_all(&self) -> Result<()> {
         let mut core = self.core.lock().unwrap();
         core.wait_all().await
@@ -124,6 +144,19 @@ impl StreamManagerCore {
             actors: HashMap::new(),
             fragments: HashMap::new(),
             mock_source: (Some(tx), Some(rx)),
+            own_addr: None,
+        }
+    }
+
+    /// Whether `host` refers to this node itself, i.e. whether an actor living there should be
+    /// wired up with a local channel rather than a `RemoteOutput`/gRPC exchange stream.
+    fn is_local_address(&self, host: &stream_service::HostAddress) -> bool {
+        match &self.own_addr {
+            Some(own_addr) => {
+                host.get_host() == own_addr.get_host() && host.get_port() == own_addr.get_port()
+            }
+            // Single-node setups that never call `bind_addr` keep working as before.
+            None => host.get_host() == "127.0.0.1",
         }
     }
 
@@ -135,14 +168,32 @@ impl StreamManagerCore {
         fragment_id: u32,
         downstreams: &[u32],
     ) -> Box<dyn StreamConsumer> {
-        // create downstream receivers
-        let outputs = self
+        // `update_fragment` only pre-creates local channels for downstreams that live on this
+        // node, in the same order as `downstreams`; a downstream that isn't local gets a
+        // `RemoteOutput` below instead.
+        let mut local_senders = self
             .channel_pool
             .get_mut(&fragment_id)
             .map(|x| std::mem::take(&mut x.0))
             .unwrap_or_default()
-            .into_iter()
-            .map(|tx| Box::new(ChannelOutput::new(tx)) as Box<dyn Output>)
+            .into_iter();
+
+        let outputs = downstreams
+            .iter()
+            .map(|downstream_id| -> Box<dyn Output> {
+                let downstream_actor = self
+                    .actors
+                    .get(downstream_id)
+                    .expect("downstream actor not found in info table");
+                if self.is_local_address(downstream_actor.get_host()) {
+                    let tx = local_senders
+                        .next()
+                        .expect("local downstream channel missing for a local downstream actor");
+                    Box::new(ChannelOutput::new(tx))
+                } else {
+                    Box::new(RemoteOutput::new(fragment_id, *downstream_id))
+                }
+            })
             .collect::<Vec<_>>();
 
         assert_eq!(downstreams.len(), outputs.len());
@@ -292,12 +343,18 @@ impl StreamManagerCore {
                 .actors
                 .get(upstream)
                 .expect("upstream actor not found in info table");
-            // FIXME: use `is_local_address` from `ExchangeExecutor`.
-            if actor.get_host().get_host() == "127.0.0.1" {
+            if self.is_local_address(actor.get_host()) {
+                // The receiver for this upstream is already in `rxs`, taken from `channel_pool`
+                // above.
                 continue;
             } else {
-                todo!("remote node is not supported in streaming engine");
-                // TODO: create gRPC connection
+                let receiver = new_remote_input(
+                    actor.get_host().get_host(),
+                    actor.get_host().get_port() as u16,
+                    *upstream,
+                    fragment_id,
+                )?;
+                rxs.push(receiver);
             }
         }
 
@@ -379,6 +436,19 @@ impl StreamManagerCore {
             for downstream in fragment.get_downstream_fragment_id() {
                 // At this time, the graph might not be complete, so we do not check if downstream has `current_id`
                 // as upstream.
+                //
+                // A downstream on a remote node doesn't get a local channel at all: its output is
+                // wired up as a `RemoteOutput` in `create_dispatcher` instead. `update_actor_info`
+                // is expected to have already run by this point; if we somehow don't know the
+                // downstream's address yet, default to a local channel rather than silently
+                // dropping it.
+                let downstream_is_remote = self
+                    .actors
+                    .get(downstream)
+                    .is_some_and(|actor| !self.is_local_address(actor.get_host()));
+                if downstream_is_remote {
+                    continue;
+                }
                 let (tx, rx) = channel(LOCAL_OUTPUT_CHANNEL_SIZE);
                 let current_channels = self.channel_pool.entry(*current_id).or_default();
                 current_channels.0.push(tx);
@@ -0,0 +1,55 @@
+use futures::StreamExt;
+use grpcio::{RpcContext, ServerStreamingSink, WriteFlags};
+use risingwave_proto::stream_service::{self, ExchangeRequest};
+
+use crate::stream_op::Message;
+
+use super::remote_exchange::take_outbox;
+
+/// Implements the server side of the `exchange` RPC that [`super::new_remote_input`] dials into:
+/// a remote node asks for a `(fragment_id, downstream_fragment_id)` pair, we take the matching
+/// outbox [`RemoteOutput`](super::RemoteOutput) registered it, and stream whatever it produces
+/// onto the sink until either side closes. Registered with the gRPC server alongside this node's
+/// other services at startup.
+#[derive(Clone, Default)]
+pub struct StreamExchangeService;
+
+impl stream_service::StreamService for StreamExchangeService {
+    fn exchange(
+        &mut self,
+        ctx: RpcContext<'_>,
+        req: ExchangeRequest,
+        sink: ServerStreamingSink<stream_service::Message>,
+    ) {
+        let fragment_id = req.get_fragment_id();
+        let downstream_fragment_id = req.get_downstream_fragment_id();
+        let outbox = take_outbox(fragment_id, downstream_fragment_id);
+        ctx.spawn(async move {
+            let mut receiver = match outbox {
+                Some(receiver) => receiver,
+                None => {
+                    log::warn!(
+                        "exchange request for unknown outbox (fragment_id={}, downstream_fragment_id={})",
+                        fragment_id,
+                        downstream_fragment_id
+                    );
+                    return;
+                }
+            };
+            let mut sink = sink;
+            while let Some(message) = receiver.next().await {
+                let proto_message = message.to_protobuf();
+                if let Err(e) = sink.send((proto_message, WriteFlags::default())).await {
+                    log::warn!(
+                        "exchange stream for (fragment_id={}, downstream_fragment_id={}) broke: {}",
+                        fragment_id,
+                        downstream_fragment_id,
+                        e
+                    );
+                    return;
+                }
+            }
+            let _ = sink.close().await;
+        });
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use futures::channel::mpsc::{channel, Receiver, Sender};
+use futures::{SinkExt, StreamExt};
+use grpcio::{ChannelBuilder, Environment};
+use log::error;
+use once_cell::sync::Lazy;
+use risingwave_proto::stream_service;
+
+use crate::error::{ErrorCode, Result, RwError};
+use crate::stream_op::{Message, Output};
+
+/// Bound of the gRPC exchange stream buffer: how many un-consumed `Message`s we allow to queue up
+/// before backpressuring the producer, so a slow downstream actor can't let a fast upstream actor
+/// run this node out of memory.
+const EXCHANGE_BUFFER_SIZE: usize = 16;
+
+/// Outboxes for actors that have a downstream on a remote node, keyed by
+/// `(fragment_id, downstream_fragment_id)` rather than `fragment_id` alone: a dispatcher
+/// (`BROADCAST`/`HASH`/...) can fan out to several remote downstreams at once, each getting its
+/// own `RemoteOutput` and therefore needing its own slot, or the second `RemoteOutput::new` call
+/// for the same producer fragment would silently clobber the first's entry before anything
+/// consumed it. [`RemoteOutput`] pushes into the `Sender` half, and
+/// [`super::exchange_service::StreamExchangeService`] takes the `Receiver` half out of here via
+/// [`take_outbox`] to stream to whichever remote node dials in asking for that pair.
+static OUTBOXES: Lazy<Mutex<HashMap<(u32, u32), Receiver<Message>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Takes the receiving end of the outbox registered for `(fragment_id, downstream_fragment_id)`,
+/// if any. Called by [`super::exchange_service::StreamExchangeService`] once per incoming
+/// connection; a given pair can only be consumed by one remote node's stream at a time, same as a
+/// local channel only has one reader.
+pub(super) fn take_outbox(fragment_id: u32, downstream_fragment_id: u32) -> Option<Receiver<Message>> {
+    OUTBOXES
+        .lock()
+        .unwrap()
+        .remove(&(fragment_id, downstream_fragment_id))
+}
+
+/// Opens a gRPC exchange stream to `upstream_host`:`upstream_port` asking for the output
+/// `upstream_fragment_id` registered for us specifically (`own_fragment_id`), and returns the
+/// receiving end of a channel fed by a background task that decodes the stream into `Message`s.
+/// From the caller's perspective (`MergeOperator`/`ReceiverOperator`) this is indistinguishable
+/// from a local channel receiver; a broken connection surfaces as the channel closing, which the
+/// actor's run loop turns into a terminating error propagated through its `JoinHandle`.
+pub fn new_remote_input(
+    upstream_host: &str,
+    upstream_port: u16,
+    upstream_fragment_id: u32,
+    own_fragment_id: u32,
+) -> Result<Receiver<Message>> {
+    let env = std::sync::Arc::new(Environment::new(1));
+    let channel = ChannelBuilder::new(env).connect(&format!("{upstream_host}:{upstream_port}"));
+    let client = stream_service::StreamServiceClient::new(channel);
+
+    let mut req = stream_service::ExchangeRequest::default();
+    req.set_fragment_id(upstream_fragment_id);
+    req.set_downstream_fragment_id(own_fragment_id);
+    let mut resp_stream = client.exchange(&req).map_err(|e| {
+        RwError::from(ErrorCode::InternalError(format!(
+            "failed to open exchange stream to {upstream_host}:{upstream_port} for fragment \
+             {upstream_fragment_id} (downstream {own_fragment_id}): {e}"
+        )))
+    })?;
+
+    let (mut tx, rx) = channel(EXCHANGE_BUFFER_SIZE);
+    tokio::spawn(async move {
+        while let Some(next) = resp_stream.next().await {
+            let proto_message = match next {
+                Ok(m) => m,
+                Err(e) => {
+                    error!(
+                        "remote exchange stream for fragment {} broke: {}",
+                        upstream_fragment_id, e
+                    );
+                    break;
+                }
+            };
+            let message = match Message::from_protobuf(&proto_message) {
+                Ok(m) => m,
+                Err(e) => {
+                    error!("failed to decode remote exchange message: {}", e);
+                    break;
+                }
+            };
+            if tx.send(message).await.is_err() {
+                // The actor holding the other end has shut down; nothing more to forward.
+                break;
+            }
+        }
+    });
+
+    Ok(rx)
+}
+
+/// An [`Output`] for one remote downstream of a dispatcher. It doesn't dial out itself: the
+/// remote node's own `create_merger` is the one that calls [`new_remote_input`] to pull from us,
+/// so `RemoteOutput` just feeds the outbox that the exchange gRPC service streams out of. Each
+/// remote downstream of a fan-out dispatcher gets its own `RemoteOutput`/outbox pair, keyed by
+/// `(fragment_id, downstream_fragment_id)` so a `BROADCAST`/`HASH` dispatcher with several remote
+/// downstreams doesn't have one `RemoteOutput::new` clobber another's outbox.
+pub struct RemoteOutput {
+    fragment_id: u32,
+    downstream_fragment_id: u32,
+    sender: Sender<Message>,
+}
+
+impl RemoteOutput {
+    /// Registers a fresh outbox for `(fragment_id, downstream_fragment_id)` and returns the
+    /// `Output` that feeds it.
+    pub fn new(fragment_id: u32, downstream_fragment_id: u32) -> Self {
+        let (sender, receiver) = channel(EXCHANGE_BUFFER_SIZE);
+        OUTBOXES
+            .lock()
+            .unwrap()
+            .insert((fragment_id, downstream_fragment_id), receiver);
+        Self {
+            fragment_id,
+            downstream_fragment_id,
+            sender,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Output for RemoteOutput {
+    async fn collect(&mut self, msg: Message) -> Result<()> {
+        self.sender.send(msg).await.map_err(|e| {
+            RwError::from(ErrorCode::InternalError(format!(
+                "remote output for (fragment_id={}, downstream_fragment_id={}) failed: {}",
+                self.fragment_id, self.downstream_fragment_id, e
+            )))
+        })
+    }
+}
@@ -0,0 +1,103 @@
+// Copyright 2023 RisingWave Labs
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Offline tool that streams every column family from one `MetaStore` backend into another, so
+//! operators can move between the embedded SQLite and LMDB backends without losing Hummock
+//! version history.
+//!
+//! Which column families exist is discovered from the source backend itself (see
+//! `SqliteMetaStore::list_column_families`/`LmdbMetaStore::list_column_families`) rather than from
+//! a fixed list maintained here: a real meta node accumulates many more CFs over time
+//! (versioning, catalog, actors, compaction, ...) than this tool could ever enumerate up front,
+//! and a fixed list would silently drop every CF it doesn't happen to know about.
+
+use clap::Parser;
+use risingwave_meta::storage::lmdb_meta_store::LmdbMetaStore;
+use risingwave_meta::storage::sqlite_meta_store::SqliteMetaStore;
+use risingwave_meta::storage::MetaStore;
+
+/// Generous upper bound on how many column families a single meta node accumulates, used only to
+/// size LMDB's `max_dbs`; unlike the old fixed CF list this is never used to decide which CFs get
+/// migrated.
+const LMDB_MAX_COLUMN_FAMILIES: u32 = 256;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum Backend {
+    Sqlite,
+    Lmdb,
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Migrate meta store data between embedded backends")]
+struct Args {
+    #[arg(long, value_enum)]
+    from_backend: Backend,
+    #[arg(long)]
+    from_path: std::path::PathBuf,
+    #[arg(long, value_enum)]
+    to_backend: Backend,
+    #[arg(long)]
+    to_path: std::path::PathBuf,
+}
+
+enum OpenedStore {
+    Sqlite(SqliteMetaStore),
+    Lmdb(LmdbMetaStore),
+}
+
+impl OpenedStore {
+    fn as_meta_store(&self) -> &dyn MetaStore {
+        match self {
+            OpenedStore::Sqlite(s) => s,
+            OpenedStore::Lmdb(s) => s,
+        }
+    }
+}
+
+async fn open(backend: Backend, path: &std::path::Path) -> OpenedStore {
+    match backend {
+        Backend::Sqlite => OpenedStore::Sqlite(SqliteMetaStore::open(path).await.unwrap()),
+        Backend::Lmdb => {
+            OpenedStore::Lmdb(LmdbMetaStore::open(path, LMDB_MAX_COLUMN_FAMILIES).unwrap())
+        }
+    }
+}
+
+/// Discovers the column families populated in `store`. A free function rather than a method on
+/// `OpenedStore` since it just dispatches to whichever backend's own
+/// `list_column_families` (both `async`, each handing its underlying query to `spawn_blocking`).
+async fn discover_column_families(store: &OpenedStore) -> Vec<String> {
+    match store {
+        OpenedStore::Sqlite(s) => s.list_column_families().await.unwrap(),
+        OpenedStore::Lmdb(s) => s.list_column_families().await.unwrap(),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let from = open(args.from_backend, &args.from_path).await;
+    let to = open(args.to_backend, &args.to_path).await;
+
+    let column_families = discover_column_families(&from).await;
+    println!("discovered {} column families to migrate", column_families.len());
+    for cf in column_families {
+        let entries = from.as_meta_store().list_cf(&cf).await.unwrap();
+        println!("{cf}: migrating {} entries", entries.len());
+        for (key, value) in entries {
+            to.as_meta_store().put_cf(&cf, key, value).await.unwrap();
+        }
+    }
+    println!("done");
+}
@@ -14,12 +14,16 @@
 
 use std::ops::Bound::{Excluded, Included};
 use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 use function_name::named;
 use itertools::Itertools;
 use risingwave_hummock_sdk::version_checkpoint_dir;
+use risingwave_pb::hummock::group_delta::DeltaType;
 use risingwave_pb::hummock::hummock_version_checkpoint::StaleObjects;
-use risingwave_pb::hummock::HummockVersionCheckpoint;
+use risingwave_pb::hummock::{HummockVersionCheckpoint, HummockVersionDelta};
 
 use crate::hummock::error::Result;
 use crate::hummock::manager::{read_lock, write_lock};
@@ -27,8 +31,50 @@ use crate::hummock::metrics_utils::trigger_stale_ssts_stat;
 use crate::hummock::HummockManager;
 use crate::storage::{MetaStore, MetaStoreError, DEFAULT_COLUMN_FAMILY};
 
+mod checksum;
+mod multipart_upload;
+mod object_crypto;
+mod refcount_gc;
+mod security_opts;
+
+pub(crate) use checksum::ChecksumAlgorithm;
+use checksum::ChecksumError;
+pub(crate) use object_crypto::{CipherAlgorithm, ObjectCipher};
+pub(crate) use refcount_gc::ObjectRefcountGc;
+use security_opts::HummockObjectSecurityOpts;
+
 const HUMMOCK_INIT_FLAG_KEY: &[u8] = b"hummock_init_flag";
 
+/// How often the background resync worker sweeps [`ObjectRefcountGc`] tombstones whose grace
+/// window has elapsed. Started lazily (see [`RESYNC_WORKER_STARTED`]) the first time a checkpoint
+/// is created, since that's the first point at which this process's `object_store` and meta store
+/// are known to both be live.
+const OBJECT_REFCOUNT_RESYNC_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Guards against starting more than one resync worker per process, analogous to how
+/// `stream_manager`'s remote exchange keeps a single outbox registry alive for the process rather
+/// than one per actor.
+static RESYNC_WORKER_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Returns the object ids every `IntraLevel` delta in `delta` inserts, i.e. the SSTs this delta
+/// adds a reference to. The mirror image of `delta.gc_object_ids`, which lists the ones it drops.
+fn inserted_object_ids(delta: &HummockVersionDelta) -> Vec<risingwave_hummock_sdk::HummockSstableObjectId> {
+    delta
+        .group_deltas
+        .values()
+        .flat_map(|group_deltas| group_deltas.group_deltas.iter())
+        .filter_map(|group_delta| group_delta.delta_type.as_ref())
+        .flat_map(|delta_type| match delta_type {
+            DeltaType::IntraLevel(intra_level) => intra_level
+                .inserted_table_infos
+                .iter()
+                .map(|sst| sst.object_id)
+                .collect(),
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
 /// A hummock version checkpoint compacts previous hummock version delta logs, and stores stale
 /// objects from those delta logs.
 impl<S> HummockManager<S>
@@ -54,20 +100,153 @@ where
             .object_store
             .read(&self.version_checkpoint_path, None)
             .await?;
-        let ckpt = HummockVersionCheckpoint::decode(data).map_err(|e| anyhow::anyhow!(e))?;
+        // Verify end-to-end integrity first, distinct from the "object not found" case above: the
+        // object exists but is corrupted, either by the storage backend (checksum mismatch) or by
+        // tampering (auth tag mismatch, surfaced by `open_framed_parts` below).
+        let framed = checksum::strip_and_verify_trailer(&data).map_err(|e| match e {
+            ChecksumError::ChunkChecksumMismatch { .. } => {
+                anyhow::anyhow!("checkpoint object failed checksum verification: {e}")
+            }
+            e => anyhow::anyhow!(e),
+        })?;
+        let framed = multipart_upload::strip_part_index(framed)?;
+        let cipher = self.object_cipher();
+        let data = multipart_upload::open_framed_parts(
+            &self.version_checkpoint_path,
+            framed,
+            cipher.as_ref(),
+        )?;
+        let ckpt =
+            HummockVersionCheckpoint::decode(data.as_slice()).map_err(|e| anyhow::anyhow!(e))?;
         Ok(Some(ckpt))
     }
 
+    /// Fetches and decrypts a single part of the checkpoint object, independent of every other
+    /// part: a bounded tail read gets the checksum trailer and the part index appended just
+    /// before it (see the `multipart_upload` module doc for the object layout), then one ranged
+    /// read fetches exactly that part's bytes. This is what makes the per-part nonces in
+    /// `object_cipher` actually pay off for random-range reads, rather than only bounding memory
+    /// use during a full sequential read.
+    pub(crate) async fn read_checkpoint_part(&self, part_no: usize) -> Result<Vec<u8>> {
+        // A generous bound on how large the part index plus checksum trailer can get; comfortably
+        // covers a checkpoint with many thousands of parts.
+        const TAIL_READ_LEN: usize = 1024 * 1024;
+        let metadata = self
+            .object_store
+            .list(&version_checkpoint_dir(&self.version_checkpoint_path))
+            .await?
+            .into_iter()
+            .find(|o| o.key == self.version_checkpoint_path)
+            .ok_or_else(|| anyhow::anyhow!("checkpoint object not found"))?;
+        let total_size = metadata.total_size;
+        let tail_start = total_size.saturating_sub(TAIL_READ_LEN);
+        let tail = self
+            .object_store
+            .read(&self.version_checkpoint_path, Some(tail_start..total_size))
+            .await?;
+        let (trailer_len, _table) =
+            checksum::parse_trailer(&tail).map_err(|e| anyhow::anyhow!(e))?;
+        let index_tail = &tail[..tail.len() - trailer_len];
+        let (_index_len, part_index) =
+            multipart_upload::PartIndex::parse(index_tail).map_err(|e| anyhow::anyhow!(e))?;
+        let range = part_index.part_range(part_no).map_err(|e| anyhow::anyhow!(e))?;
+        let framed_part = self
+            .object_store
+            .read(&self.version_checkpoint_path, Some(range))
+            .await?;
+        let cipher = self.object_cipher();
+        multipart_upload::open_one_framed_part(
+            &self.version_checkpoint_path,
+            part_no,
+            &framed_part,
+            cipher.as_ref(),
+        )
+    }
+
+    /// Validates and returns a single [`checksum::CHECKSUM_CHUNK_SIZE`]-aligned chunk of the
+    /// checkpoint object, without reading or rehashing the rest of it: a bounded tail read gets
+    /// the checksum trailer (see [`checksum::parse_trailer`]), then a single ranged read fetches
+    /// just the requested chunk, which is verified against its own stored digest via
+    /// [`checksum::ChecksumTable::verify_chunk`]. This is the range-read path the module doc on
+    /// `checksum` describes; `read_checkpoint` above still does a full read for the common case.
+    pub(crate) async fn read_checkpoint_chunk(&self, chunk_index: usize) -> Result<Vec<u8>> {
+        // A generous bound on how large a trailer (chunk digest table + count + algo byte) can
+        // get; comfortably covers even a multi-GiB object hashed with SHA-256.
+        const TAIL_READ_LEN: usize = 1024 * 1024;
+        let metadata = self
+            .object_store
+            .list(&version_checkpoint_dir(&self.version_checkpoint_path))
+            .await?
+            .into_iter()
+            .find(|o| o.key == self.version_checkpoint_path)
+            .ok_or_else(|| anyhow::anyhow!("checkpoint object not found"))?;
+        let total_size = metadata.total_size;
+        let tail_start = total_size.saturating_sub(TAIL_READ_LEN);
+        let tail = self
+            .object_store
+            .read(&self.version_checkpoint_path, Some(tail_start..total_size))
+            .await?;
+        let (trailer_len, table) =
+            checksum::parse_trailer(&tail).map_err(|e| anyhow::anyhow!(e))?;
+        let payload_len = total_size - trailer_len;
+        let range = table
+            .chunk_range(chunk_index, payload_len)
+            .map_err(|e| anyhow::anyhow!(e))?;
+        let chunk_data = self
+            .object_store
+            .read(&self.version_checkpoint_path, Some(range))
+            .await?;
+        table
+            .verify_chunk(chunk_index, &chunk_data)
+            .map_err(|e| anyhow::anyhow!("checkpoint chunk {chunk_index} failed checksum verification: {e}"))?;
+        Ok(chunk_data.to_vec())
+    }
+
     pub(super) async fn write_checkpoint(
         &self,
         checkpoint: &HummockVersionCheckpoint,
     ) -> Result<()> {
         use prost::Message;
         let buf = checkpoint.encode_to_vec();
-        self.object_store
-            .upload(&self.version_checkpoint_path, buf.into())
-            .await?;
-        Ok(())
+        multipart_upload::upload_checkpoint(
+            &self.object_store,
+            &self.version_checkpoint_path,
+            buf,
+            self.object_cipher().as_ref(),
+            self.object_checksum_algorithm(),
+        )
+        .await
+    }
+
+    /// Selects the checksum algorithm used for the end-to-end integrity trailer appended to every
+    /// object (checkpoints here, and, via this being `pub(crate)`, any SST writer elsewhere in the
+    /// crate), defaulting to the cheaper CRC32C unless the operator asks for SHA-256.
+    pub(crate) fn object_checksum_algorithm(&self) -> ChecksumAlgorithm {
+        if HummockObjectSecurityOpts::from_env().strong_checksum {
+            ChecksumAlgorithm::Sha256
+        } else {
+            ChecksumAlgorithm::Crc32C
+        }
+    }
+
+    /// Builds the reference-counted GC helper backing the tombstone/resync flow used by
+    /// `create_version_checkpoint` below.
+    pub(crate) fn object_refcount_gc(&self) -> ObjectRefcountGc<S> {
+        ObjectRefcountGc::new(self.env.meta_store())
+    }
+
+    /// Builds the object cipher used to seal/open checkpoints (and, via [`Self::object_cipher`]
+    /// being `pub(crate)`, any SST writer elsewhere in the crate) at rest, if a master key is
+    /// configured. Operators that don't configure one keep writing plaintext objects, same as
+    /// before this was added.
+    pub(crate) fn object_cipher(&self) -> Option<ObjectCipher> {
+        let opts = HummockObjectSecurityOpts::from_env();
+        let master_key = opts.encryption_key?;
+        Some(ObjectCipher::new(
+            master_key,
+            opts.encryption_key_id,
+            CipherAlgorithm::XChaCha20Poly1305,
+        ))
     }
 
     /// Creates a hummock version checkpoint.
@@ -88,14 +267,21 @@ where
             return Ok(0);
         }
         let mut stale_objects = old_checkpoint.stale_objects.clone();
+        // Still kept for backward compatibility (older checkpoints and external tooling read
+        // `stale_objects`), and `mark_objects_for_deletion` below still derives deletions from
+        // this set; `ObjectRefcountGc`'s tombstone/resync flow below runs alongside it.
+        let mut all_removed_object_ids = Vec::new();
+        let mut all_inserted_object_ids = Vec::new();
         for (_, version_delta) in versioning
             .hummock_version_deltas
             .range((Excluded(old_checkpoint_id), Included(new_checkpoint_id)))
         {
+            all_inserted_object_ids.extend(inserted_object_ids(version_delta));
             let removed_object_ids = version_delta.gc_object_ids.clone();
             if removed_object_ids.is_empty() {
                 continue;
             }
+            all_removed_object_ids.extend(removed_object_ids.iter().copied());
             stale_objects.insert(
                 version_delta.id,
                 StaleObjects {
@@ -110,6 +296,26 @@ where
         drop(versioning_guard);
         // 2. persist the new checkpoint without holding lock
         self.write_checkpoint(&new_checkpoint).await?;
+        // Increments are applied from the same delta range the decrements below are derived
+        // from, so an object inserted and later removed within this checkpoint interval nets out
+        // correctly without ever spuriously dropping to a zero refcount in between.
+        let object_refcount_gc = self.object_refcount_gc();
+        object_refcount_gc
+            .increment_refs(&all_inserted_object_ids)
+            .await?;
+        // Decrementing refs may drop some objects to zero and tombstone them for the resync
+        // worker to reclaim once their grace window elapses; it must not happen before the new
+        // checkpoint (which may still reference them) is durably persisted.
+        object_refcount_gc
+            .decrement_refs(&all_removed_object_ids)
+            .await?;
+        // Lazily start the tombstone resync worker the first time we ever create a checkpoint,
+        // i.e. the first point at which we know `self.object_store` is live. Only one worker ever
+        // runs per process, so later checkpoints are a no-op here.
+        if !RESYNC_WORKER_STARTED.swap(true, Ordering::SeqCst) {
+            Arc::new(object_refcount_gc)
+                .start_resync_worker(self.object_store.clone(), OBJECT_REFCOUNT_RESYNC_INTERVAL);
+        }
         // 3. hold write lock and update in memory state
         let mut versioning_guard = write_lock!(self, versioning).await;
         let mut versioning = versioning_guard.deref_mut();
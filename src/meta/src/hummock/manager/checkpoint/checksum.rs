@@ -0,0 +1,354 @@
+// Copyright 2023 RisingWave Labs
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! End-to-end checksums for objects written through the object store, appended as a trailer so
+//! corruption introduced by the storage backend (rather than by us) can be detected on read.
+//!
+//! The trailer stores one digest per [`CHECKSUM_CHUNK_SIZE`]-aligned chunk of the payload, not
+//! just a single digest-of-digests: [`strip_and_verify_trailer`] still verifies every chunk for
+//! the common "read the whole object" case, but [`parse_trailer`] plus [`ChecksumTable::verify_chunk`]
+//! lets a caller that only fetched a byte range of the object (see
+//! `HummockManager::read_checkpoint_chunk`) validate just the chunks that range overlaps, without
+//! rereading or rehashing the rest of the object.
+
+use std::ops::Range;
+
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+/// Chunk size used to compute the per-chunk checksum table. Not tied to the object store's
+/// multipart part size; chosen independently so checksums stay a fixed, small overhead for small
+/// objects.
+pub const CHECKSUM_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum ChecksumAlgorithm {
+    Crc32C = 1,
+    Sha256 = 2,
+}
+
+impl ChecksumAlgorithm {
+    fn from_u8(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Self::Crc32C),
+            2 => Some(Self::Sha256),
+            _ => None,
+        }
+    }
+
+    fn digest_len(self) -> usize {
+        match self {
+            Self::Crc32C => 4,
+            Self::Sha256 => 32,
+        }
+    }
+
+    fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Crc32C => crc32c::crc32c(data).to_le_bytes().to_vec(),
+            Self::Sha256 => Sha256::digest(data).to_vec(),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ChecksumError {
+    #[error("object is too short to contain a checksum trailer")]
+    Truncated,
+    #[error("object checksum trailer uses unsupported algorithm id {0}")]
+    UnsupportedAlgorithm(u8),
+    #[error(
+        "chunk {chunk_index} checksum mismatch: object may have been silently corrupted by the \
+         storage backend (expected {expected:x?}, got {actual:x?})"
+    )]
+    ChunkChecksumMismatch {
+        chunk_index: usize,
+        expected: Vec<u8>,
+        actual: Vec<u8>,
+    },
+    #[error("chunk index {chunk_index} out of range (trailer covers {num_chunks} chunks)")]
+    ChunkIndexOutOfRange {
+        chunk_index: usize,
+        num_chunks: usize,
+    },
+}
+
+fn chunk_digests(algorithm: ChecksumAlgorithm, data: &[u8]) -> Vec<u8> {
+    data.chunks(CHECKSUM_CHUNK_SIZE)
+        .flat_map(|chunk| algorithm.digest(chunk))
+        .collect()
+}
+
+/// The parsed per-chunk digest table from a trailer appended by [`append_trailer`]. Doesn't know
+/// the payload's total length on its own -- [`Self::chunk_range`] takes it as a parameter, since a
+/// caller validating a range it already fetched learns the total object size from the object
+/// store's listing, not from the trailer.
+pub struct ChecksumTable {
+    algorithm: ChecksumAlgorithm,
+    /// Concatenated per-chunk digests, `algorithm.digest_len()` bytes each.
+    chunk_digests: Vec<u8>,
+}
+
+impl ChecksumTable {
+    pub fn num_chunks(&self) -> usize {
+        self.chunk_digests.len() / self.algorithm.digest_len()
+    }
+
+    /// The byte range `chunk_index` occupies within the (pre-trailer) payload, given the
+    /// payload's total length -- enough for a caller to issue a ranged read for exactly the bytes
+    /// [`Self::verify_chunk`] needs.
+    pub fn chunk_range(
+        &self,
+        chunk_index: usize,
+        payload_len: usize,
+    ) -> Result<Range<usize>, ChecksumError> {
+        let num_chunks = self.num_chunks();
+        if chunk_index >= num_chunks {
+            return Err(ChecksumError::ChunkIndexOutOfRange {
+                chunk_index,
+                num_chunks,
+            });
+        }
+        let start = chunk_index * CHECKSUM_CHUNK_SIZE;
+        let end = (start + CHECKSUM_CHUNK_SIZE).min(payload_len);
+        Ok(start..end)
+    }
+
+    /// Verifies `chunk_data` (the bytes at [`Self::chunk_range`]) against the digest stored for
+    /// `chunk_index`, without touching any other chunk of the object.
+    pub fn verify_chunk(
+        &self,
+        chunk_index: usize,
+        chunk_data: &[u8],
+    ) -> Result<(), ChecksumError> {
+        let num_chunks = self.num_chunks();
+        if chunk_index >= num_chunks {
+            return Err(ChecksumError::ChunkIndexOutOfRange {
+                chunk_index,
+                num_chunks,
+            });
+        }
+        let digest_len = self.algorithm.digest_len();
+        let expected =
+            &self.chunk_digests[chunk_index * digest_len..(chunk_index + 1) * digest_len];
+        let actual = self.algorithm.digest(chunk_data);
+        if actual != expected {
+            return Err(ChecksumError::ChunkChecksumMismatch {
+                chunk_index,
+                expected: expected.to_vec(),
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Appends a checksum trailer to `payload`: one digest per [`CHECKSUM_CHUNK_SIZE`]-aligned chunk,
+/// followed by the chunk count and algorithm id needed to parse it back out:
+/// `payload || chunk_digest* || num_chunks(u32 LE) || algorithm_id(1)`.
+pub fn append_trailer(algorithm: ChecksumAlgorithm, mut payload: Vec<u8>) -> Vec<u8> {
+    let digests = chunk_digests(algorithm, &payload);
+    let num_chunks = (digests.len() / algorithm.digest_len()) as u32;
+    payload.extend_from_slice(&digests);
+    payload.extend_from_slice(&num_chunks.to_le_bytes());
+    payload.push(algorithm as u8);
+    payload
+}
+
+/// Parses the trailer appended by [`append_trailer`] out of `data`, which must include the
+/// trailer's bytes (its very end) but need not include the rest of the payload -- e.g. a
+/// sufficiently large tail read rather than the whole object. Returns the trailer's own byte
+/// length, so the caller can work out where the payload it didn't fetch would have ended, and the
+/// parsed [`ChecksumTable`].
+pub fn parse_trailer(data: &[u8]) -> Result<(usize, ChecksumTable), ChecksumError> {
+    let algo_id = *data.last().ok_or(ChecksumError::Truncated)?;
+    let algorithm =
+        ChecksumAlgorithm::from_u8(algo_id).ok_or(ChecksumError::UnsupportedAlgorithm(algo_id))?;
+    let without_algo = &data[..data.len() - 1];
+    if without_algo.len() < 4 {
+        return Err(ChecksumError::Truncated);
+    }
+    let (without_count, count_bytes) = without_algo.split_at(without_algo.len() - 4);
+    let num_chunks = u32::from_le_bytes(count_bytes.try_into().unwrap()) as usize;
+    let table_len = num_chunks * algorithm.digest_len();
+    if without_count.len() < table_len {
+        return Err(ChecksumError::Truncated);
+    }
+    let chunk_digests = without_count[without_count.len() - table_len..].to_vec();
+    let trailer_len = 1 + 4 + table_len;
+    Ok((
+        trailer_len,
+        ChecksumTable {
+            algorithm,
+            chunk_digests,
+        },
+    ))
+}
+
+/// Strips and verifies the checksum trailer appended by [`append_trailer`], returning the
+/// original payload on success. For the common case of a reader that fetched the whole object;
+/// [`parse_trailer`] plus [`ChecksumTable::verify_chunk`] is the path for a reader that only
+/// fetched part of it.
+pub fn strip_and_verify_trailer(data: &[u8]) -> Result<&[u8], ChecksumError> {
+    let (trailer_len, table) = parse_trailer(data)?;
+    if data.len() < trailer_len {
+        return Err(ChecksumError::Truncated);
+    }
+    let payload = &data[..data.len() - trailer_len];
+    for chunk_index in 0..table.num_chunks() {
+        let range = table
+            .chunk_range(chunk_index, payload.len())
+            .expect("chunk_index is in range by construction of the loop above");
+        table.verify_chunk(chunk_index, &payload[range])?;
+    }
+    Ok(payload)
+}
+
+/// Incremental version of [`append_trailer`] that only ever needs one [`CHECKSUM_CHUNK_SIZE`]
+/// chunk in memory at a time, for use while streaming multipart parts to the object store rather
+/// than holding the whole payload in memory to checksum it at the end.
+pub struct StreamingChecksum {
+    algorithm: ChecksumAlgorithm,
+    chunk_digests: Vec<u8>,
+    buffer: Vec<u8>,
+}
+
+impl StreamingChecksum {
+    pub fn new(algorithm: ChecksumAlgorithm) -> Self {
+        Self {
+            algorithm,
+            chunk_digests: Vec::new(),
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let take = (CHECKSUM_CHUNK_SIZE - self.buffer.len()).min(data.len());
+            self.buffer.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buffer.len() == CHECKSUM_CHUNK_SIZE {
+                self.chunk_digests.extend(self.algorithm.digest(&self.buffer));
+                self.buffer.clear();
+            }
+        }
+    }
+
+    /// Flushes any partial final chunk and returns the trailer, in the same format
+    /// [`append_trailer`] appends.
+    pub fn finish_trailer(mut self) -> Vec<u8> {
+        if !self.buffer.is_empty() {
+            self.chunk_digests.extend(self.algorithm.digest(&self.buffer));
+        }
+        let num_chunks = (self.chunk_digests.len() / self.algorithm.digest_len()) as u32;
+        let mut trailer = self.chunk_digests;
+        trailer.extend_from_slice(&num_chunks.to_le_bytes());
+        trailer.push(self.algorithm as u8);
+        trailer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_crc32c() {
+        let payload = b"hummock checkpoint payload".to_vec();
+        let sealed = append_trailer(ChecksumAlgorithm::Crc32C, payload.clone());
+        assert_eq!(strip_and_verify_trailer(&sealed).unwrap(), payload);
+    }
+
+    #[test]
+    fn roundtrip_sha256_multi_chunk() {
+        let payload = vec![0x42u8; CHECKSUM_CHUNK_SIZE * 2 + 17];
+        let sealed = append_trailer(ChecksumAlgorithm::Sha256, payload.clone());
+        assert_eq!(strip_and_verify_trailer(&sealed).unwrap(), payload);
+    }
+
+    #[test]
+    fn streaming_checksum_matches_composite() {
+        let payload = vec![0x99u8; CHECKSUM_CHUNK_SIZE + 123];
+        let mut streaming = StreamingChecksum::new(ChecksumAlgorithm::Sha256);
+        for chunk in payload.chunks(1337) {
+            streaming.update(chunk);
+        }
+        let streaming_trailer = streaming.finish_trailer();
+        let batch_trailer = {
+            let sealed = append_trailer(ChecksumAlgorithm::Sha256, payload.clone());
+            sealed[payload.len()..].to_vec()
+        };
+        assert_eq!(streaming_trailer, batch_trailer);
+    }
+
+    #[test]
+    fn detects_corruption() {
+        let payload = b"hummock checkpoint payload".to_vec();
+        let mut sealed = append_trailer(ChecksumAlgorithm::Sha256, payload);
+        let corrupt_idx = 3;
+        sealed[corrupt_idx] ^= 0xff;
+        assert!(matches!(
+            strip_and_verify_trailer(&sealed),
+            Err(ChecksumError::ChunkChecksumMismatch { chunk_index: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn verifies_a_single_chunk_from_only_a_tail_read() {
+        // Simulates a caller that only fetched the tail of the object (enough to cover the
+        // trailer) plus a ranged read of one chunk, never the chunks in between.
+        let payload = vec![0x07u8; CHECKSUM_CHUNK_SIZE * 3 + 42];
+        let sealed = append_trailer(ChecksumAlgorithm::Sha256, payload.clone());
+
+        let tail_len = sealed.len() - payload.len() + 16; // trailer plus a little slack
+        let tail = &sealed[sealed.len() - tail_len..];
+        let (trailer_len, table) = parse_trailer(tail).unwrap();
+        let payload_len = sealed.len() - trailer_len;
+        assert_eq!(payload_len, payload.len());
+
+        let chunk_index = 2;
+        let range = table.chunk_range(chunk_index, payload_len).unwrap();
+        table.verify_chunk(chunk_index, &payload[range]).unwrap();
+    }
+
+    #[test]
+    fn rejects_wrong_chunk_data() {
+        let payload = vec![0x11u8; CHECKSUM_CHUNK_SIZE * 2];
+        let sealed = append_trailer(ChecksumAlgorithm::Crc32C, payload.clone());
+        let (trailer_len, table) = parse_trailer(&sealed).unwrap();
+        let payload_len = sealed.len() - trailer_len;
+        let range = table.chunk_range(0, payload_len).unwrap();
+        let wrong_data = vec![0x22u8; range.len()];
+        assert!(matches!(
+            table.verify_chunk(0, &wrong_data),
+            Err(ChecksumError::ChunkChecksumMismatch { chunk_index: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn chunk_index_out_of_range_is_rejected() {
+        let payload = vec![0x11u8; CHECKSUM_CHUNK_SIZE];
+        let sealed = append_trailer(ChecksumAlgorithm::Crc32C, payload.clone());
+        let (trailer_len, table) = parse_trailer(&sealed).unwrap();
+        let payload_len = sealed.len() - trailer_len;
+        assert!(matches!(
+            table.chunk_range(1, payload_len),
+            Err(ChecksumError::ChunkIndexOutOfRange {
+                chunk_index: 1,
+                num_chunks: 1
+            })
+        ));
+    }
+}
@@ -0,0 +1,476 @@
+// Copyright 2023 RisingWave Labs
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Streaming multipart upload for large Hummock version checkpoints.
+//!
+//! A single `object_store.upload` of the whole encoded checkpoint pins the entire buffer in
+//! memory a second time on the way into the object store client, and leaves no way to recover if
+//! the upload fails partway through other than retrying the whole thing. This module splits the
+//! payload into fixed-size, independently-sealed parts, each framed with its own length prefix so
+//! they can be told apart again on read, and drives them through the object store's multipart
+//! primitives. Every part is sealed (the CPU-bound encryption step) before the first upload
+//! starts, one part after another on the calling task, and the `upload_part` calls are strictly
+//! sequential too: `StreamingUploader::upload_part`
+//! takes `&mut self` and no explicit part index, so the object store client can only infer a
+//! part's position from the order calls arrive in -- uploading out of order (or concurrently,
+//! which gives no ordering guarantee at all) would silently corrupt the object. Only once every
+//! part (plus the part index and the checksum trailer) has succeeded do we `finish` the upload, so
+//! a failure partway through leaves no object visible at all rather than a truncated one; objects
+//! small enough to fit in a single part skip multipart entirely and go through a plain `upload`.
+//!
+//! Object layout: `(len: u32 LE, sealed_part)* || part_index || checksum_trailer`, where
+//! `part_index` is `(offset: u64 LE, framed_len: u32 LE)* || num_parts(u32 LE)` -- see
+//! [`append_part_index`]. Storing each part's absolute byte offset means a reader that wants a
+//! single part (`HummockManager::read_checkpoint_part`) can fetch just the index (a bounded tail
+//! read, not the whole object) and then issue one ranged read for exactly that part's bytes,
+//! rather than parsing every preceding part's length prefix from byte 0.
+
+use std::future::Future;
+use std::ops::Range;
+
+use futures::stream::{self, StreamExt};
+
+use super::checksum::{ChecksumAlgorithm, StreamingChecksum};
+use super::object_crypto::ObjectCipher;
+use crate::hummock::error::Result;
+use crate::storage::object_store::{ObjectStoreRef, StreamingUploader};
+
+/// Size of each logical part, also used as the physical multipart part size. Kept within S3's
+/// multipart bounds (5 MiB - 5 GiB per part) and large enough that per-part overhead doesn't
+/// dominate.
+const PART_SIZE: usize = 8 * 1024 * 1024;
+/// How many sealing futures the `buffered` stream in [`seal_framed_parts`] keeps in flight. Each
+/// one seals synchronously when first polled, so parts are still sealed one at a time.
+const MAX_CONCURRENT_PARTS: usize = 4;
+const MAX_PART_UPLOAD_ATTEMPTS: u32 = 3;
+/// Size of a directory entry in the part index: an 8-byte offset plus a 4-byte length.
+const PART_INDEX_ENTRY_LEN: usize = 8 + 4;
+
+fn part_path(path: &str, part_no: usize) -> String {
+    format!("{path}#part-{part_no}")
+}
+
+/// Seals `raw` (if `cipher` is set) as one or more independently-encrypted, length-framed parts.
+/// Empty input yields a single empty part, with no length prefix.
+async fn seal_framed_parts(path: &str, raw: &[u8], cipher: Option<&ObjectCipher>) -> Vec<Vec<u8>> {
+    if raw.is_empty() {
+        return vec![Vec::new()];
+    }
+    stream::iter(raw.chunks(PART_SIZE).enumerate())
+        .map(|(part_no, chunk)| async move {
+            let sealed = match cipher {
+                Some(cipher) => cipher.seal(&part_path(path, part_no), chunk),
+                None => chunk.to_vec(),
+            };
+            let mut framed = Vec::with_capacity(4 + sealed.len());
+            framed.extend_from_slice(&(sealed.len() as u32).to_le_bytes());
+            framed.extend_from_slice(&sealed);
+            framed
+        })
+        .buffered(MAX_CONCURRENT_PARTS)
+        .collect()
+        .await
+}
+
+/// Reverses [`seal_framed_parts`]: parses the length-prefixed, independently-sealed parts out of
+/// `framed` and returns the concatenated plaintext. `framed` must contain only the framed parts
+/// themselves (see [`strip_part_index`] for stripping the directory appended after them).
+pub(super) fn open_framed_parts(
+    path: &str,
+    framed: &[u8],
+    cipher: Option<&ObjectCipher>,
+) -> Result<Vec<u8>> {
+    let mut plaintext = Vec::with_capacity(framed.len());
+    let mut offset = 0;
+    let mut part_no = 0;
+    while offset < framed.len() {
+        if framed.len() - offset < 4 {
+            return Err(anyhow::anyhow!("truncated checkpoint part framing").into());
+        }
+        let len = u32::from_le_bytes(framed[offset..offset + 4].try_into().unwrap()) as usize;
+        offset += 4;
+        if framed.len() - offset < len {
+            return Err(anyhow::anyhow!("truncated checkpoint part body").into());
+        }
+        let sealed = &framed[offset..offset + len];
+        offset += len;
+        match cipher {
+            Some(cipher) => plaintext.extend(
+                cipher
+                    .open(&part_path(path, part_no), sealed)
+                    .map_err(|e| anyhow::anyhow!("checkpoint part {part_no} failed to open: {e}"))?,
+            ),
+            None => plaintext.extend_from_slice(sealed),
+        }
+        part_no += 1;
+    }
+    Ok(plaintext)
+}
+
+/// Opens a single already-located framed part (the bytes at the range returned by
+/// [`PartIndex::part_range`]), independent of every other part -- the read path
+/// `HummockManager::read_checkpoint_part` is built around.
+pub(super) fn open_one_framed_part(
+    path: &str,
+    part_no: usize,
+    framed_part: &[u8],
+    cipher: Option<&ObjectCipher>,
+) -> Result<Vec<u8>> {
+    if framed_part.len() < 4 {
+        return Err(anyhow::anyhow!("truncated checkpoint part framing").into());
+    }
+    let len = u32::from_le_bytes(framed_part[..4].try_into().unwrap()) as usize;
+    let sealed = framed_part
+        .get(4..4 + len)
+        .ok_or_else(|| anyhow::anyhow!("truncated checkpoint part body"))?;
+    match cipher {
+        Some(cipher) => cipher
+            .open(&part_path(path, part_no), sealed)
+            .map_err(|e| anyhow::anyhow!("checkpoint part {part_no} failed to open: {e}").into()),
+        None => Ok(sealed.to_vec()),
+    }
+}
+
+/// Appends the part index directly after the concatenated `framed_parts`: one
+/// `(offset: u64 LE, framed_len: u32 LE)` entry per part, followed by the part count, so a reader
+/// who only fetched the tail of the object (enough to cover this index plus the checksum
+/// trailer) can look up exactly where any one part lives without touching the rest of the object.
+fn append_part_index(mut payload: Vec<u8>, framed_parts: &[Vec<u8>]) -> Vec<u8> {
+    let mut offset = 0u64;
+    for framed in framed_parts {
+        payload.extend_from_slice(&offset.to_le_bytes());
+        payload.extend_from_slice(&(framed.len() as u32).to_le_bytes());
+        offset += framed.len() as u64;
+    }
+    payload.extend_from_slice(&(framed_parts.len() as u32).to_le_bytes());
+    payload
+}
+
+/// A part index parsed by [`PartIndex::parse`], giving the byte range of any one part within the
+/// object without needing to have read the parts themselves.
+pub(super) struct PartIndex {
+    entries: Vec<(u64, u32)>,
+}
+
+impl PartIndex {
+    /// Parses a part index out of `tail`, which must include the index's own bytes (its very
+    /// end) but need not include the framed parts before it -- e.g. a bounded tail read. Returns
+    /// the index's own byte length alongside the parsed index.
+    pub(super) fn parse(tail: &[u8]) -> Result<(usize, Self)> {
+        if tail.len() < 4 {
+            return Err(anyhow::anyhow!("truncated checkpoint part index").into());
+        }
+        let (rest, count_bytes) = tail.split_at(tail.len() - 4);
+        let num_parts = u32::from_le_bytes(count_bytes.try_into().unwrap()) as usize;
+        let table_len = num_parts * PART_INDEX_ENTRY_LEN;
+        if rest.len() < table_len {
+            return Err(anyhow::anyhow!("truncated checkpoint part index").into());
+        }
+        let table = &rest[rest.len() - table_len..];
+        let entries = table
+            .chunks_exact(PART_INDEX_ENTRY_LEN)
+            .map(|entry| {
+                let offset = u64::from_le_bytes(entry[..8].try_into().unwrap());
+                let framed_len = u32::from_le_bytes(entry[8..].try_into().unwrap());
+                (offset, framed_len)
+            })
+            .collect();
+        Ok((table_len + 4, Self { entries }))
+    }
+
+    pub(super) fn num_parts(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The byte range `part_no`'s framed bytes occupy within the object.
+    pub(super) fn part_range(&self, part_no: usize) -> Result<Range<usize>> {
+        let (offset, framed_len) = *self.entries.get(part_no).ok_or_else(|| {
+            anyhow::anyhow!("part {part_no} out of range (index has {} parts)", self.entries.len())
+        })?;
+        let offset = offset as usize;
+        Ok(offset..offset + framed_len as usize)
+    }
+}
+
+/// Strips a part index appended by [`append_part_index`] off the end of `payload`, returning just
+/// the framed parts that precede it.
+pub(super) fn strip_part_index(payload: &[u8]) -> Result<&[u8]> {
+    let (index_len, _index) = PartIndex::parse(payload)?;
+    if payload.len() < index_len {
+        return Err(anyhow::anyhow!("truncated checkpoint part index").into());
+    }
+    Ok(&payload[..payload.len() - index_len])
+}
+
+/// Uploads every part in `parts`, in order, making up to [`MAX_PART_UPLOAD_ATTEMPTS`] attempts
+/// (the first try included) at each one individually via `upload_one`. Strictly sequential -- see the module doc
+/// for why `upload_one` can't safely be parallelized here. Factored out as a plain,
+/// closure-parameterized helper (rather than taking the concrete `StreamingUploader` trait
+/// object directly) so the ordering and retry behavior can be exercised with a local fake in
+/// tests, without needing to implement the real (and, from this crate, opaque) trait.
+async fn upload_parts_sequentially<F, Fut>(parts: Vec<Vec<u8>>, mut upload_one: F) -> Result<()>
+where
+    F: FnMut(usize, Vec<u8>) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    for (part_no, part) in parts.into_iter().enumerate() {
+        let mut last_err = None;
+        let mut uploaded = false;
+        for attempt in 0..MAX_PART_UPLOAD_ATTEMPTS {
+            match upload_one(part_no, part.clone()).await {
+                Ok(()) => {
+                    uploaded = true;
+                    break;
+                }
+                Err(e) => {
+                    tracing::warn!(part_no, attempt, error = %e, "checkpoint part upload failed, retrying");
+                    last_err = Some(e);
+                }
+            }
+        }
+        if !uploaded {
+            return Err(last_err.unwrap());
+        }
+    }
+    Ok(())
+}
+
+/// Seals, indexes, checksums and uploads `raw` to `path`. Objects that fit in a single part are
+/// uploaded with a plain `upload` call; larger objects are streamed through the object store's
+/// multipart primitives, each part uploaded in order with individual retries, and abort the
+/// in-flight upload on any part or trailer failure so no partial object is ever visible.
+pub(super) async fn upload_checkpoint(
+    object_store: &ObjectStoreRef,
+    path: &str,
+    raw: Vec<u8>,
+    cipher: Option<&ObjectCipher>,
+    checksum_algorithm: ChecksumAlgorithm,
+) -> Result<()> {
+    let framed_parts = seal_framed_parts(path, &raw, cipher).await;
+
+    if framed_parts.len() <= 1 {
+        let body = framed_parts.into_iter().next().unwrap_or_default();
+        // Every object layout (single-part or multipart) carries a part index before the
+        // checksum trailer, even though a single-part object only ever has one entry in it, so
+        // read_checkpoint/read_checkpoint_part don't need to special-case which path wrote it.
+        let part_index = append_part_index(Vec::new(), std::slice::from_ref(&body));
+        let mut checksum = StreamingChecksum::new(checksum_algorithm);
+        checksum.update(&body);
+        checksum.update(&part_index);
+        let mut sealed = body;
+        sealed.extend_from_slice(&part_index);
+        sealed.extend(checksum.finish_trailer());
+        object_store.upload(path, sealed.into()).await?;
+        return Ok(());
+    }
+
+    let mut uploader = object_store.streaming_upload(path).await?;
+    let part_index = append_part_index(Vec::new(), &framed_parts);
+    let mut checksum = StreamingChecksum::new(checksum_algorithm);
+    for part in &framed_parts {
+        checksum.update(part);
+    }
+    checksum.update(&part_index);
+    let trailer = checksum.finish_trailer();
+
+    let result: Result<()> = async {
+        upload_parts_sequentially(framed_parts, |_part_no, part| async {
+            uploader.upload_part(part.into()).await.map_err(Into::into)
+        })
+        .await?;
+        // The part index and checksum trailer are themselves uploaded as trailing parts,
+        // immediately after the real parts and in the same strictly-ordered fashion.
+        upload_parts_sequentially(vec![part_index, trailer], |_part_no, part| async {
+            uploader.upload_part(part.into()).await.map_err(Into::into)
+        })
+        .await
+    }
+    .await;
+
+    match result {
+        Ok(()) => uploader.finish().await.map_err(Into::into),
+        Err(e) => {
+            // A partial multipart upload must never become visible; abort rather than leave a
+            // truncated object for the next reader to trip over.
+            let _ = uploader.abort().await;
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::sync::Mutex;
+
+    use super::*;
+    use crate::hummock::manager::checkpoint::object_crypto::CipherAlgorithm;
+
+    #[tokio::test]
+    async fn seal_and_open_round_trip_without_cipher() {
+        let raw = b"hello hummock checkpoint payload".repeat(10);
+        let parts = seal_framed_parts("test/path", &raw, None).await;
+        let framed: Vec<u8> = parts.into_iter().flatten().collect();
+        let opened = open_framed_parts("test/path", &framed, None).unwrap();
+        assert_eq!(opened, raw);
+    }
+
+    #[tokio::test]
+    async fn seal_and_open_round_trip_with_cipher_multiple_parts() {
+        let cipher = ObjectCipher::new([9u8; 32], 1, CipherAlgorithm::XChaCha20Poly1305);
+        let raw = vec![7u8; PART_SIZE * 2 + 123];
+        let parts = seal_framed_parts("test/path", &raw, Some(&cipher)).await;
+        assert_eq!(parts.len(), 3);
+        let framed: Vec<u8> = parts.into_iter().flatten().collect();
+        let opened = open_framed_parts("test/path", &framed, Some(&cipher)).unwrap();
+        assert_eq!(opened, raw);
+    }
+
+    #[tokio::test]
+    async fn seal_empty_input_yields_single_empty_part() {
+        let parts = seal_framed_parts("test/path", &[], None).await;
+        assert_eq!(parts, vec![Vec::<u8>::new()]);
+    }
+
+    #[test]
+    fn open_rejects_truncated_length_prefix() {
+        let framed = vec![0u8, 0, 0];
+        assert!(open_framed_parts("test/path", &framed, None).is_err());
+    }
+
+    #[test]
+    fn open_rejects_truncated_part_body() {
+        let mut framed = Vec::new();
+        framed.extend_from_slice(&10u32.to_le_bytes());
+        framed.extend_from_slice(b"short");
+        assert!(open_framed_parts("test/path", &framed, None).is_err());
+    }
+
+    #[tokio::test]
+    async fn part_index_locates_every_part_independently() {
+        let cipher = ObjectCipher::new([3u8; 32], 1, CipherAlgorithm::Aes256Gcm);
+        let raw = vec![5u8; PART_SIZE * 2 + 77];
+        let framed_parts = seal_framed_parts("test/path", &raw, Some(&cipher)).await;
+        let concatenated: Vec<u8> = framed_parts.iter().flatten().copied().collect();
+        let payload = append_part_index(concatenated.clone(), &framed_parts);
+
+        let tail_len = 4096.min(payload.len());
+        let tail = &payload[payload.len() - tail_len..];
+        let (index_len, index) = PartIndex::parse(tail).unwrap();
+        assert_eq!(index_len, payload.len() - concatenated.len());
+        assert_eq!(index.num_parts(), framed_parts.len());
+
+        for (part_no, framed_part) in framed_parts.iter().enumerate() {
+            let range = index.part_range(part_no).unwrap();
+            assert_eq!(&payload[range], framed_part.as_slice());
+        }
+    }
+
+    #[tokio::test]
+    async fn a_single_part_can_be_fetched_and_decrypted_without_the_others() {
+        // Exercises the exact sequence HummockManager::read_checkpoint_part uses: locate one
+        // part's byte range via the index, "ranged read" just those bytes (a plain slice here,
+        // standing in for an object store ranged read), then open it on its own.
+        let cipher = ObjectCipher::new([6u8; 32], 1, CipherAlgorithm::XChaCha20Poly1305);
+        let raw = vec![9u8; PART_SIZE + 256];
+        let framed_parts = seal_framed_parts("test/path", &raw, Some(&cipher)).await;
+        let concatenated: Vec<u8> = framed_parts.iter().flatten().copied().collect();
+        let payload = append_part_index(concatenated.clone(), &framed_parts);
+        let (_, index) = PartIndex::parse(&payload).unwrap();
+
+        let part_no = 1;
+        let range = index.part_range(part_no).unwrap();
+        let fetched = &payload[range];
+        let opened = open_one_framed_part("test/path", part_no, fetched, Some(&cipher)).unwrap();
+        assert_eq!(opened, raw[PART_SIZE..]);
+    }
+
+    #[test]
+    fn part_index_rejects_out_of_range_part() {
+        let framed_parts = vec![vec![1, 2, 3], vec![4, 5]];
+        let payload = append_part_index(Vec::new(), &framed_parts);
+        let (_, index) = PartIndex::parse(&payload).unwrap();
+        assert!(index.part_range(2).is_err());
+    }
+
+    #[test]
+    fn strip_part_index_recovers_exact_framed_parts() {
+        let framed_parts = vec![vec![1, 2, 3], vec![4, 5, 6, 7]];
+        let concatenated: Vec<u8> = framed_parts.iter().flatten().copied().collect();
+        let payload = append_part_index(concatenated.clone(), &framed_parts);
+        assert_eq!(strip_part_index(&payload).unwrap(), concatenated.as_slice());
+    }
+
+    #[tokio::test]
+    async fn parts_upload_in_strict_order() {
+        let landed = Arc::new(Mutex::new(Vec::new()));
+        let parts: Vec<Vec<u8>> = (0..8u8).map(|i| vec![i; 4]).collect();
+        let expected: Vec<u8> = parts.iter().flatten().copied().collect();
+
+        upload_parts_sequentially(parts, {
+            let landed = landed.clone();
+            move |_part_no, part| {
+                let landed = landed.clone();
+                async move {
+                    landed.lock().await.extend(part);
+                    Ok(())
+                }
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(landed.lock().await.as_slice(), expected.as_slice());
+    }
+
+    #[tokio::test]
+    async fn retries_a_failing_part_before_giving_up_on_the_whole_batch() {
+        let attempts = Arc::new(Mutex::new(0u32));
+        let parts = vec![vec![1u8], vec![2u8]];
+        let result = upload_parts_sequentially(parts, {
+            let attempts = attempts.clone();
+            move |part_no, _part| {
+                let attempts = attempts.clone();
+                async move {
+                    if part_no == 1 {
+                        let mut n = attempts.lock().await;
+                        *n += 1;
+                        if *n < MAX_PART_UPLOAD_ATTEMPTS {
+                            return Err(anyhow::anyhow!("transient failure").into());
+                        }
+                    }
+                    Ok(())
+                }
+            }
+        })
+        .await;
+        assert!(result.is_ok());
+        assert_eq!(*attempts.lock().await, MAX_PART_UPLOAD_ATTEMPTS);
+    }
+
+    #[tokio::test]
+    async fn a_permanently_failing_part_fails_the_whole_batch() {
+        let parts: Vec<Vec<u8>> = (0..4u8).map(|i| vec![i]).collect();
+        let result = upload_parts_sequentially(parts, |part_no, _part| async move {
+            if part_no == 2 {
+                Err(anyhow::anyhow!("disk full").into())
+            } else {
+                Ok(())
+            }
+        })
+        .await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,297 @@
+// Copyright 2023 RisingWave Labs
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reference-counted garbage collection for Hummock objects (SSTs).
+//!
+//! Instead of deriving the stale-object set by scanning `hummock_version_deltas` between two
+//! checkpoints, every object carries a persistent refcount in the meta store. A version delta
+//! that drops an SST decrements its refcount; once it reaches zero we don't delete the object
+//! immediately (an in-flight reader or a pinned older version may still be using it), but write a
+//! tombstone stamped with the earliest time it's safe to reclaim. A background worker sweeps
+//! tombstones whose grace window has elapsed, double-checks the refcount is still zero, deletes
+//! the object, and only then clears the tombstone. This makes reclamation crash-safe (a tombstone
+//! left behind after a crash is simply picked up by the next resync pass) and decouples "is this
+//! object still referenced" from "does a stale-object scan over two checkpoints say so".
+//!
+//! `increment_refs`/`decrement_refs` are so far only called from
+//! `HummockManager::create_version_checkpoint`, over the same delta range the old stale-object
+//! scan used -- so refcounts are still only ever updated on checkpoint cadence, not per delta as
+//! committed. Moving those calls to wherever version deltas are actually committed (so refcounts
+//! track deltas directly, independent of when the next checkpoint happens to land) is out of
+//! scope for this change.
+
+use std::time::Duration;
+
+use risingwave_hummock_sdk::HummockSstableObjectId;
+
+use crate::hummock::error::Result;
+use crate::storage::MetaStore;
+
+/// Column family storing `object_id -> refcount` (little-endian u64).
+const OBJECT_REFCOUNT_CF: &str = "cf/hummock_object_refcount";
+/// Column family storing `object_id -> deletion_eligible_at_unix_millis` (little-endian u64) for
+/// objects whose refcount has reached zero but haven't cleared the grace window yet.
+const OBJECT_TOMBSTONE_CF: &str = "cf/hummock_object_tombstone";
+
+/// How long a zero-refcount object sits as a tombstone before the resync worker is allowed to
+/// actually delete it from the object store, to avoid racing an in-flight reader that obtained a
+/// pinned version just before the delta was applied.
+const DEFAULT_GC_GRACE_WINDOW: Duration = Duration::from_secs(600);
+
+/// Maximum number of attempts the resync worker makes to delete a single object before giving up
+/// on it for this pass and retrying on the next one.
+const MAX_DELETE_ATTEMPTS: u32 = 5;
+
+pub struct ObjectRefcountGc<S: MetaStore> {
+    meta_store: std::sync::Arc<S>,
+    grace_window: Duration,
+}
+
+impl<S: MetaStore> ObjectRefcountGc<S> {
+    pub fn new(meta_store: std::sync::Arc<S>) -> Self {
+        Self {
+            meta_store,
+            grace_window: DEFAULT_GC_GRACE_WINDOW,
+        }
+    }
+
+    fn refcount_key(object_id: HummockSstableObjectId) -> Vec<u8> {
+        object_id.to_be_bytes().to_vec()
+    }
+
+    async fn get_refcount(&self, object_id: HummockSstableObjectId) -> Result<u64> {
+        match self
+            .meta_store
+            .get_cf(OBJECT_REFCOUNT_CF, &Self::refcount_key(object_id))
+            .await
+        {
+            Ok(bytes) => Ok(u64::from_le_bytes(bytes.try_into().unwrap())),
+            Err(crate::storage::MetaStoreError::ItemNotFound(_)) => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Increments the refcount of every object a version delta adds. Called from
+    /// `HummockManager::create_version_checkpoint` for the deltas the new checkpoint covers, not
+    /// when each delta is committed (see the module doc).
+    pub async fn increment_refs(&self, object_ids: &[HummockSstableObjectId]) -> Result<()> {
+        for &object_id in object_ids {
+            let refcount = self.get_refcount(object_id).await? + 1;
+            self.meta_store
+                .put_cf(
+                    OBJECT_REFCOUNT_CF,
+                    Self::refcount_key(object_id),
+                    refcount.to_le_bytes().to_vec(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Decrements the refcount of every object a version delta removes. An object whose refcount
+    /// reaches zero is not deleted here; instead a tombstone is written with a deletion-eligible
+    /// timestamp so the resync worker can reclaim it once the grace window has elapsed.
+    pub async fn decrement_refs(&self, object_ids: &[HummockSstableObjectId]) -> Result<()> {
+        for &object_id in object_ids {
+            let refcount = self.get_refcount(object_id).await?;
+            let refcount = refcount.saturating_sub(1);
+            if refcount == 0 {
+                let deletion_eligible_at = now_unix_millis() + self.grace_window.as_millis() as u64;
+                self.meta_store
+                    .put_cf(
+                        OBJECT_TOMBSTONE_CF,
+                        Self::refcount_key(object_id),
+                        deletion_eligible_at.to_le_bytes().to_vec(),
+                    )
+                    .await?;
+            }
+            self.meta_store
+                .put_cf(
+                    OBJECT_REFCOUNT_CF,
+                    Self::refcount_key(object_id),
+                    refcount.to_le_bytes().to_vec(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Scans tombstones whose grace window has elapsed, re-checks the refcount is still zero
+    /// (an object can be resurrected if a later delta re-adds it before the grace window expires),
+    /// deletes the now-unreferenced object from `object_store`, and clears its tombstone. Failed
+    /// deletes are left in place and retried on the next call, up to [`MAX_DELETE_ATTEMPTS`] within
+    /// this pass, with an exponential backoff between attempts.
+    pub async fn resync_once(
+        &self,
+        object_store: &crate::storage::object_store::ObjectStoreRef,
+    ) -> Result<usize> {
+        let now = now_unix_millis();
+        let mut reclaimed = 0;
+        let tombstones = self.meta_store.list_cf(OBJECT_TOMBSTONE_CF).await?;
+        for (key, value) in tombstones {
+            let object_id = HummockSstableObjectId::from_be_bytes(key.clone().try_into().unwrap());
+            let deletion_eligible_at = u64::from_le_bytes(value.try_into().unwrap());
+            if now < deletion_eligible_at {
+                continue;
+            }
+            if self.get_refcount(object_id).await? != 0 {
+                // Resurrected by a later delta before the grace window elapsed.
+                self.meta_store.delete_cf(OBJECT_TOMBSTONE_CF, &key).await?;
+                continue;
+            }
+            if self
+                .delete_with_retry(object_store, object_id)
+                .await
+                .is_ok()
+            {
+                self.meta_store.delete_cf(OBJECT_TOMBSTONE_CF, &key).await?;
+                self.meta_store
+                    .delete_cf(OBJECT_REFCOUNT_CF, &key)
+                    .await?;
+                reclaimed += 1;
+            }
+        }
+        Ok(reclaimed)
+    }
+
+    async fn delete_with_retry(
+        &self,
+        object_store: &crate::storage::object_store::ObjectStoreRef,
+        object_id: HummockSstableObjectId,
+    ) -> Result<()> {
+        let mut backoff = Duration::from_millis(100);
+        let mut last_err = None;
+        for _ in 0..MAX_DELETE_ATTEMPTS {
+            match object_store.delete(&sst_object_path(object_id)).await {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    last_err = Some(e);
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+            }
+        }
+        Err(last_err.unwrap().into())
+    }
+
+    /// Spawns the background resync worker that periodically reclaims expired tombstones.
+    pub fn start_resync_worker(
+        self: std::sync::Arc<Self>,
+        object_store: crate::storage::object_store::ObjectStoreRef,
+        interval: Duration,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                match self.resync_once(&object_store).await {
+                    Ok(reclaimed) if reclaimed > 0 => {
+                        tracing::info!(reclaimed, "hummock object refcount GC reclaimed objects");
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        tracing::warn!(error = %e, "hummock object refcount GC resync failed");
+                    }
+                }
+            }
+        })
+    }
+}
+
+fn sst_object_path(object_id: HummockSstableObjectId) -> String {
+    format!("hummock/{}.sst", object_id)
+}
+
+fn now_unix_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::sqlite_meta_store::SqliteMetaStore;
+
+    async fn test_gc() -> ObjectRefcountGc<SqliteMetaStore> {
+        let meta_store = SqliteMetaStore::open(std::path::Path::new(":memory:"))
+            .await
+            .unwrap();
+        ObjectRefcountGc::new(std::sync::Arc::new(meta_store))
+    }
+
+    #[tokio::test]
+    async fn new_object_starts_at_zero_refs() {
+        let gc = test_gc().await;
+        assert_eq!(gc.get_refcount(1).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn increment_then_matching_decrement_tombstones() {
+        let gc = test_gc().await;
+        gc.increment_refs(&[1]).await.unwrap();
+        assert_eq!(gc.get_refcount(1).await.unwrap(), 1);
+        gc.decrement_refs(&[1]).await.unwrap();
+        assert_eq!(gc.get_refcount(1).await.unwrap(), 0);
+        let tombstones = gc.meta_store.list_cf(OBJECT_TOMBSTONE_CF).await.unwrap();
+        assert_eq!(tombstones.len(), 1);
+        assert_eq!(
+            tombstones[0].0,
+            ObjectRefcountGc::<SqliteMetaStore>::refcount_key(1)
+        );
+    }
+
+    #[tokio::test]
+    async fn decrement_without_a_matching_increment_saturates_at_zero() {
+        // A decrement that isn't backed by a corresponding increment saturates at zero rather
+        // than underflowing into a huge u64. Like any decrement that reaches zero, it still
+        // writes a tombstone; this test only checks the refcount.
+        let gc = test_gc().await;
+        gc.decrement_refs(&[1]).await.unwrap();
+        assert_eq!(gc.get_refcount(1).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn second_reference_survives_one_decrement() {
+        let gc = test_gc().await;
+        gc.increment_refs(&[1, 1]).await.unwrap();
+        assert_eq!(gc.get_refcount(1).await.unwrap(), 2);
+        gc.decrement_refs(&[1]).await.unwrap();
+        assert_eq!(gc.get_refcount(1).await.unwrap(), 1);
+        let tombstones = gc.meta_store.list_cf(OBJECT_TOMBSTONE_CF).await.unwrap();
+        assert!(tombstones.is_empty());
+    }
+
+    #[tokio::test]
+    async fn resurrection_before_grace_window_restores_refcount() {
+        let gc = test_gc().await;
+        gc.increment_refs(&[1]).await.unwrap();
+        gc.decrement_refs(&[1]).await.unwrap();
+        assert_eq!(
+            gc.meta_store
+                .list_cf(OBJECT_TOMBSTONE_CF)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        // A later delta re-adds the object before the grace window elapses.
+        gc.increment_refs(&[1]).await.unwrap();
+        assert_eq!(gc.get_refcount(1).await.unwrap(), 1);
+        // `resync_once` (exercised against a real `ObjectStoreRef` in integration tests) is the
+        // one that actually clears a resurrected tombstone; here we just confirm the refcount
+        // itself no longer reads as zero, which is what that check keys off of.
+    }
+}
@@ -0,0 +1,266 @@
+// Copyright 2023 RisingWave Labs
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A [`MetaStore`] backed by an embedded LMDB environment, the other single-node embedded backend
+//! alongside [`super::sqlite_meta_store`]. Each column family is a named LMDB sub-database; reads
+//! use a read-only transaction, writes and deletes use a write transaction, and a [`Transaction`]
+//! is applied as a single write transaction so it's all-or-nothing.
+//!
+//! Every method hands the actual (synchronous) `heed`/LMDB call to `spawn_blocking` rather than
+//! calling it directly from the `async fn`, the same way [`super::sqlite_meta_store`] wraps every
+//! `rusqlite` call: `heed` has no async story of its own, and `wtxn.commit()` in particular
+//! triggers a blocking fsync, which would stall every other task scheduled onto the same tokio
+//! worker thread for however long that fsync takes.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use heed::types::ByteSlice;
+use heed::{Database, Env, EnvOpenOptions};
+
+use crate::storage::{MetaStoreError, Transaction};
+
+pub struct LmdbMetaStore {
+    env: Env,
+    /// Sub-databases are opened lazily and cached here, keyed by column family name, since heed
+    /// requires a write transaction to create a new one. `Arc` (rather than a bare `RwLock`, as
+    /// [`super::sqlite_meta_store::SqliteMetaStore`] uses `Arc<Mutex<Connection>>`) so it can be
+    /// cloned into a `spawn_blocking` closure alongside `env`.
+    databases: Arc<RwLock<HashMap<String, Database<ByteSlice, ByteSlice>>>>,
+}
+
+impl LmdbMetaStore {
+    pub fn open(path: &std::path::Path, max_dbs: u32) -> Result<Self, MetaStoreError> {
+        std::fs::create_dir_all(path).map_err(|e| MetaStoreError::Internal(e.into()))?;
+        let env = EnvOpenOptions::new()
+            .max_dbs(max_dbs)
+            .open(path)
+            .map_err(|e| MetaStoreError::Internal(e.into()))?;
+        Ok(Self {
+            env,
+            databases: Arc::new(RwLock::new(HashMap::new())),
+        })
+    }
+
+    async fn database(&self, cf: &str) -> Result<Database<ByteSlice, ByteSlice>, MetaStoreError> {
+        if let Some(db) = self.databases.read().unwrap().get(cf) {
+            return Ok(*db);
+        }
+        let env = self.env.clone();
+        let databases = self.databases.clone();
+        let cf = cf.to_string();
+        tokio::task::spawn_blocking(move || {
+            if let Some(db) = databases.read().unwrap().get(&cf) {
+                return Ok(*db);
+            }
+            let mut wtxn = env.write_txn().map_err(|e| MetaStoreError::Internal(e.into()))?;
+            let db: Database<ByteSlice, ByteSlice> = env
+                .create_database(&mut wtxn, Some(&cf))
+                .map_err(|e| MetaStoreError::Internal(e.into()))?;
+            wtxn.commit().map_err(|e| MetaStoreError::Internal(e.into()))?;
+            databases.write().unwrap().insert(cf, db);
+            Ok(db)
+        })
+        .await
+        .unwrap()
+    }
+
+    /// Enumerates every column family ever created in this environment, read straight from
+    /// LMDB's unnamed root database rather than [`Self::databases`] (which only knows about the
+    /// sub-databases *this* `LmdbMetaStore` instance has touched since it was opened): LMDB
+    /// stores the name of every named sub-database as a key in the unnamed one, so a fresh
+    /// instance opened against an existing environment can recover the full set without any
+    /// separate registry of our own.
+    pub async fn list_column_families(&self) -> Result<Vec<String>, MetaStoreError> {
+        let env = self.env.clone();
+        tokio::task::spawn_blocking(move || {
+            let rtxn = env.read_txn().map_err(|e| MetaStoreError::Internal(e.into()))?;
+            let root: Option<Database<ByteSlice, ByteSlice>> = env
+                .open_database(&rtxn, None)
+                .map_err(|e| MetaStoreError::Internal(e.into()))?;
+            let Some(root) = root else {
+                return Ok(Vec::new());
+            };
+            root.iter(&rtxn)
+                .map_err(|e| MetaStoreError::Internal(e.into()))?
+                .map(|entry| {
+                    entry
+                        .map(|(name, _)| String::from_utf8_lossy(name).into_owned())
+                        .map_err(|e| MetaStoreError::Internal(e.into()))
+                })
+                .collect()
+        })
+        .await
+        .unwrap()
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::storage::MetaStore for LmdbMetaStore {
+    async fn get_cf(&self, cf: &str, key: &[u8]) -> Result<Vec<u8>, MetaStoreError> {
+        let db = self.database(cf).await?;
+        let env = self.env.clone();
+        let cf = cf.to_string();
+        let key = key.to_vec();
+        tokio::task::spawn_blocking(move || {
+            let rtxn = env.read_txn().map_err(|e| MetaStoreError::Internal(e.into()))?;
+            db.get(&rtxn, &key)
+                .map_err(|e| MetaStoreError::Internal(e.into()))?
+                .map(|v| v.to_vec())
+                .ok_or_else(|| MetaStoreError::ItemNotFound(format!("{cf}/{key:?}")))
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn put_cf(&self, cf: &str, key: Vec<u8>, value: Vec<u8>) -> Result<(), MetaStoreError> {
+        let db = self.database(cf).await?;
+        let env = self.env.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut wtxn = env.write_txn().map_err(|e| MetaStoreError::Internal(e.into()))?;
+            db.put(&mut wtxn, &key, &value)
+                .map_err(|e| MetaStoreError::Internal(e.into()))?;
+            wtxn.commit().map_err(|e| MetaStoreError::Internal(e.into()))
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn delete_cf(&self, cf: &str, key: &[u8]) -> Result<(), MetaStoreError> {
+        let db = self.database(cf).await?;
+        let env = self.env.clone();
+        let key = key.to_vec();
+        tokio::task::spawn_blocking(move || {
+            let mut wtxn = env.write_txn().map_err(|e| MetaStoreError::Internal(e.into()))?;
+            db.delete(&mut wtxn, &key)
+                .map_err(|e| MetaStoreError::Internal(e.into()))?;
+            wtxn.commit().map_err(|e| MetaStoreError::Internal(e.into()))
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn list_cf(&self, cf: &str) -> Result<Vec<(Vec<u8>, Vec<u8>)>, MetaStoreError> {
+        let db = self.database(cf).await?;
+        let env = self.env.clone();
+        tokio::task::spawn_blocking(move || {
+            let rtxn = env.read_txn().map_err(|e| MetaStoreError::Internal(e.into()))?;
+            db.iter(&rtxn)
+                .map_err(|e| MetaStoreError::Internal(e.into()))?
+                .map(|entry| {
+                    entry
+                        .map(|(k, v)| (k.to_vec(), v.to_vec()))
+                        .map_err(|e| MetaStoreError::Internal(e.into()))
+                })
+                .collect()
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn txn(&self, txn: Transaction) -> Result<(), MetaStoreError> {
+        let mut dbs = HashMap::new();
+        for cf in txn.affected_column_families() {
+            dbs.insert(cf.clone(), self.database(&cf).await?);
+        }
+        let env = self.env.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut wtxn = env.write_txn().map_err(|e| MetaStoreError::Internal(e.into()))?;
+            for op in txn.operations() {
+                match op {
+                    crate::storage::TransactionOp::Put { cf, key, value } => {
+                        dbs[&cf]
+                            .put(&mut wtxn, &key, &value)
+                            .map_err(|e| MetaStoreError::Internal(e.into()))?;
+                    }
+                    crate::storage::TransactionOp::Delete { cf, key } => {
+                        dbs[&cf]
+                            .delete(&mut wtxn, &key)
+                            .map_err(|e| MetaStoreError::Internal(e.into()))?;
+                    }
+                }
+            }
+            wtxn.commit().map_err(|e| MetaStoreError::Internal(e.into()))
+        })
+        .await
+        .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open_store() -> (tempfile::TempDir, LmdbMetaStore) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LmdbMetaStore::open(dir.path(), 16).unwrap();
+        (dir, store)
+    }
+
+    #[tokio::test]
+    async fn put_then_get_round_trips() {
+        let (_dir, store) = open_store();
+        store.put_cf("cf/a", b"k".to_vec(), b"v".to_vec()).await.unwrap();
+        assert_eq!(store.get_cf("cf/a", b"k").await.unwrap(), b"v".to_vec());
+    }
+
+    #[tokio::test]
+    async fn get_missing_key_is_item_not_found() {
+        let (_dir, store) = open_store();
+        assert!(matches!(
+            store.get_cf("cf/a", b"missing").await,
+            Err(MetaStoreError::ItemNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn delete_removes_the_key() {
+        let (_dir, store) = open_store();
+        store.put_cf("cf/a", b"k".to_vec(), b"v".to_vec()).await.unwrap();
+        store.delete_cf("cf/a", b"k").await.unwrap();
+        assert!(matches!(
+            store.get_cf("cf/a", b"k").await,
+            Err(MetaStoreError::ItemNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn list_cf_returns_every_entry() {
+        let (_dir, store) = open_store();
+        store.put_cf("cf/a", b"k1".to_vec(), b"v1".to_vec()).await.unwrap();
+        store.put_cf("cf/a", b"k2".to_vec(), b"v2".to_vec()).await.unwrap();
+        let mut entries = store.list_cf("cf/a").await.unwrap();
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec![(b"k1".to_vec(), b"v1".to_vec()), (b"k2".to_vec(), b"v2".to_vec())]
+        );
+    }
+
+    #[tokio::test]
+    async fn list_column_families_reports_every_cf_ever_created() {
+        let (_dir, store) = open_store();
+        store.put_cf("cf/a", b"k".to_vec(), b"v".to_vec()).await.unwrap();
+        store.put_cf("cf/b", b"k".to_vec(), b"v".to_vec()).await.unwrap();
+        let mut cfs = store.list_column_families().await.unwrap();
+        cfs.sort();
+        assert_eq!(cfs, vec!["cf/a".to_string(), "cf/b".to_string()]);
+    }
+
+    // No test exercises `txn` directly: `Transaction`'s constructor isn't defined anywhere in
+    // this snapshot (crate::storage's own mod.rs isn't present here, only this file and
+    // sqlite_meta_store.rs), so there's no way to build one without guessing at an API this crate
+    // doesn't let us see. get_cf/put_cf/delete_cf/list_cf/list_column_families above cover every
+    // other method, and `txn`'s body reuses the same `database`/spawn_blocking plumbing they do.
+}
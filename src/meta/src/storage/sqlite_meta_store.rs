@@ -0,0 +1,228 @@
+// Copyright 2023 RisingWave Labs
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A [`MetaStore`] backed by an embedded SQLite database, for single-node deployments that don't
+//! want to stand up an external metadata service. Each column family maps to its own table with a
+//! `BLOB` key and a `BLOB` value, and [`Transaction`]s map directly onto SQLite transactions.
+//!
+//! Every method hands the actual (synchronous) `rusqlite` call to `spawn_blocking` rather than
+//! calling it directly from the `async fn`, the same way [`SqliteMetaStore::open`] already did:
+//! `rusqlite` has no async story of its own, and running its blocking syscalls straight on a tokio
+//! worker thread would stall every other task scheduled onto it for however long the disk I/O
+//! takes.
+
+use std::sync::Arc;
+
+use rusqlite::Connection;
+use tokio::sync::Mutex;
+
+use crate::storage::{MetaStoreError, Transaction};
+
+/// Quotes a column family name for use as a SQLite table name. Column family names in this crate
+/// are fixed string constants like `"cf/default"`, never user input, so a simple quote-doubling
+/// is enough to make them safe to splice into DDL/DML.
+fn quote_table(cf: &str) -> String {
+    format!("\"{}\"", cf.replace('"', "\"\""))
+}
+
+/// Reverses [`quote_table`] well enough to recover the original column family name from a row of
+/// `sqlite_master`, i.e. strips the surrounding quotes and un-doubles any embedded ones.
+fn unquote_table(quoted: &str) -> String {
+    quoted.trim_matches('"').replace("\"\"", "\"")
+}
+
+pub struct SqliteMetaStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteMetaStore {
+    pub async fn open(path: &std::path::Path) -> Result<Self, MetaStoreError> {
+        let path = path.to_owned();
+        let conn = tokio::task::spawn_blocking(move || Connection::open(path))
+            .await
+            .unwrap()
+            .map_err(|e| MetaStoreError::Internal(e.into()))?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    async fn ensure_table(&self, cf: &str) -> Result<(), MetaStoreError> {
+        let conn = self.conn.clone();
+        let cf = cf.to_string();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.blocking_lock();
+            conn.execute(
+                &format!(
+                    "CREATE TABLE IF NOT EXISTS {} (key BLOB PRIMARY KEY, value BLOB NOT NULL)",
+                    quote_table(&cf)
+                ),
+                [],
+            )
+            .map_err(|e| MetaStoreError::Internal(e.into()))?;
+            Ok(())
+        })
+        .await
+        .unwrap()
+    }
+
+    /// Enumerates every column family that has a table in this store, by reading table names
+    /// straight out of SQLite's own `sqlite_master` catalog rather than relying on a fixed list of
+    /// known CFs maintained elsewhere. Every `MetaStore` method (reads included) creates its CF's
+    /// table via [`Self::ensure_table`], so a CF shows up once any method has touched it, even
+    /// if it holds no entries; a CF that has never been touched doesn't show up.
+    pub async fn list_column_families(&self) -> Result<Vec<String>, MetaStoreError> {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.blocking_lock();
+            let mut stmt = conn
+                .prepare("SELECT name FROM sqlite_master WHERE type = 'table'")
+                .map_err(|e| MetaStoreError::Internal(e.into()))?;
+            let names = stmt
+                .query_map([], |row| row.get::<_, String>(0))
+                .map_err(|e| MetaStoreError::Internal(e.into()))?;
+            names
+                .map(|name| name.map(|n| unquote_table(&n)))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| MetaStoreError::Internal(e.into()))
+        })
+        .await
+        .unwrap()
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::storage::MetaStore for SqliteMetaStore {
+    async fn get_cf(&self, cf: &str, key: &[u8]) -> Result<Vec<u8>, MetaStoreError> {
+        self.ensure_table(cf).await?;
+        let conn = self.conn.clone();
+        let cf = cf.to_string();
+        let key = key.to_vec();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.blocking_lock();
+            conn.query_row(
+                &format!("SELECT value FROM {} WHERE key = ?1", quote_table(&cf)),
+                [&key],
+                |row| row.get(0),
+            )
+            .map_err(|e| match e {
+                rusqlite::Error::QueryReturnedNoRows => {
+                    MetaStoreError::ItemNotFound(format!("{cf}/{key:?}"))
+                }
+                e => MetaStoreError::Internal(e.into()),
+            })
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn put_cf(&self, cf: &str, key: Vec<u8>, value: Vec<u8>) -> Result<(), MetaStoreError> {
+        self.ensure_table(cf).await?;
+        let conn = self.conn.clone();
+        let cf = cf.to_string();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.blocking_lock();
+            conn.execute(
+                &format!(
+                    "INSERT INTO {} (key, value) VALUES (?1, ?2) \
+                     ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+                    quote_table(&cf)
+                ),
+                rusqlite::params![key, value],
+            )
+            .map_err(|e| MetaStoreError::Internal(e.into()))?;
+            Ok(())
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn delete_cf(&self, cf: &str, key: &[u8]) -> Result<(), MetaStoreError> {
+        self.ensure_table(cf).await?;
+        let conn = self.conn.clone();
+        let cf = cf.to_string();
+        let key = key.to_vec();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.blocking_lock();
+            conn.execute(
+                &format!("DELETE FROM {} WHERE key = ?1", quote_table(&cf)),
+                [&key],
+            )
+            .map_err(|e| MetaStoreError::Internal(e.into()))?;
+            Ok(())
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn list_cf(&self, cf: &str) -> Result<Vec<(Vec<u8>, Vec<u8>)>, MetaStoreError> {
+        self.ensure_table(cf).await?;
+        let conn = self.conn.clone();
+        let cf = cf.to_string();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.blocking_lock();
+            let mut stmt = conn
+                .prepare(&format!("SELECT key, value FROM {}", quote_table(&cf)))
+                .map_err(|e| MetaStoreError::Internal(e.into()))?;
+            let rows = stmt
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+                .map_err(|e| MetaStoreError::Internal(e.into()))?;
+            rows.collect::<Result<Vec<_>, _>>()
+                .map_err(|e| MetaStoreError::Internal(e.into()))
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn txn(&self, txn: Transaction) -> Result<(), MetaStoreError> {
+        for cf in txn.affected_column_families() {
+            self.ensure_table(&cf).await?;
+        }
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.blocking_lock();
+            apply_transaction_sqlite(&conn, &txn)
+        })
+        .await
+        .unwrap()
+    }
+}
+
+fn apply_transaction_sqlite(conn: &Connection, txn: &Transaction) -> Result<(), MetaStoreError> {
+    conn.execute_batch("BEGIN")
+        .map_err(|e| MetaStoreError::Internal(e.into()))?;
+    for op in txn.operations() {
+        let result = match op {
+            crate::storage::TransactionOp::Put { cf, key, value } => conn.execute(
+                &format!(
+                    "INSERT INTO {} (key, value) VALUES (?1, ?2) \
+                     ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+                    quote_table(cf)
+                ),
+                rusqlite::params![key, value],
+            ),
+            crate::storage::TransactionOp::Delete { cf, key } => conn.execute(
+                &format!("DELETE FROM {} WHERE key = ?1", quote_table(cf)),
+                [key],
+            ),
+        };
+        if let Err(e) = result {
+            let _ = conn.execute_batch("ROLLBACK");
+            return Err(MetaStoreError::Internal(e.into()));
+        }
+    }
+    conn.execute_batch("COMMIT")
+        .map_err(|e| MetaStoreError::Internal(e.into()))?;
+    Ok(())
+}